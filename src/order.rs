@@ -1,6 +1,6 @@
 use rand::distributions::{Alphanumeric, DistString};
 use std::cmp::Ordering;
-use std::collections::{BinaryHeap, HashMap};
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet};
 use std::time::{self, Duration, SystemTime};
 
 #[derive(Debug, Copy, Clone)]
@@ -9,6 +9,51 @@ pub enum OrderSide {
     Ask,
 }
 
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OrderType {
+    // Rests on the book at its limit price until matched or cancelled.
+    Limit,
+    // Matches against the opposite book at whatever prices are available;
+    // never rests.
+    Market,
+    // Matches what it can immediately at its limit price; any unfilled
+    // remainder is discarded instead of resting.
+    ImmediateOrCancel,
+    // Only executes if the full quantity can be filled immediately at its
+    // limit price; otherwise it is rejected with no state change.
+    FillOrKill,
+    // Rests at `reference + offset` instead of a fixed price, and is
+    // re-priced when a book operation moves the referenced side's best
+    // price (see `reprice_pegged_orders`).
+    Pegged { reference: PegReference, offset: i64 },
+}
+
+// What a `Pegged` order's price tracks. Currently only the opposite side's
+// best price is supported (a bid pegs to the best ask and vice versa),
+// which is what lets market-makers hug the spread automatically.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PegReference {
+    Best,
+}
+
+// Terminal state of an order after `add_order` has processed it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OrderStatus {
+    Rested,
+    PartiallyFilled,
+    FullyFilled,
+    Rejected,
+}
+
+// Outcome of submitting an order to the book, including any trades it produced.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OrderResult {
+    pub order_id: i64,
+    pub filled_quantity: i64,
+    pub status: OrderStatus,
+    pub fills: Vec<Fill>,
+}
+
 #[derive(Debug, Clone)]
 pub struct OrderIndex {
     id: i64,
@@ -61,9 +106,45 @@ pub struct Order {
     pub fulfiller_user_id: Option<String>,
     pub is_fulfilled: bool,
     pub price: i64,
+    pub quantity: i64,
+    pub remaining: i64,
     pub order_side: OrderSide,
+    pub order_type: OrderType,
     pub created_at: time::SystemTime,
+    // `None` is GoodTillCancel; `Some(t)` is GoodTillTime and makes the
+    // order invisible to matching once `now >= t`.
+    pub expires_at: Option<time::SystemTime>,
 }
+
+// A single trade between a maker and a taker: either two resting orders
+// crossed by `match_order`, or an incoming order matched by `add_order`
+// against a resting one.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Fill {
+    pub maker_id: i64,
+    pub taker_id: i64,
+    pub price: i64,
+    pub quantity: i64,
+    pub timestamp: time::SystemTime,
+}
+
+// Aggregated resting quantity at a single price level.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PriceLevel {
+    pub price: i64,
+    pub total_quantity: i64,
+    pub order_count: usize,
+}
+
+// An L2 market depth snapshot: the top price levels on each side, bids
+// ordered best-to-worst (descending) and asks ordered best-to-worst
+// (ascending).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BookDepth {
+    pub bids: Vec<PriceLevel>,
+    pub asks: Vec<PriceLevel>,
+}
+
 // Main order book for all stickers
 pub struct OrderBook {
     sticker_order_map: HashMap<String, StickerOrderBook>,
@@ -75,22 +156,139 @@ impl OrderBook {
         }
     }
 }
+// Rejects an order before it ever touches the book. These mirror the
+// price/quantity grid a real exchange enforces so the book never ends up
+// fragmented with dust orders or off-grid prices.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OrderError {
+    // `price` is not a multiple of the book's `tick_size`.
+    InvalidTick,
+    // `quantity` is not a multiple of the book's `lot_size`.
+    InvalidLot,
+    // `quantity` is below the book's `min_size`.
+    BelowMinimum,
+}
+
 // Order book for each individual sticker
 pub struct StickerOrderBook {
     order_map: HashMap<i64, Order>,
     order_queue_ask: BinaryHeap<OrderIndex>,
     order_queue_bid: BinaryHeap<OrderIndex>,
+    tick_size: i64,
+    lot_size: i64,
+    min_size: i64,
 }
 impl StickerOrderBook {
-    fn new() -> Self {
+    fn new(tick_size: i64, lot_size: i64, min_size: i64) -> Self {
+        assert!(tick_size > 0, "tick_size must be positive");
+        assert!(lot_size > 0, "lot_size must be positive");
         StickerOrderBook {
             order_map: HashMap::new(),
             order_queue_ask: BinaryHeap::new(),
             order_queue_bid: BinaryHeap::new(),
+            tick_size,
+            lot_size,
+            min_size,
+        }
+    }
+
+    // Rejects orders whose price/quantity don't sit on this book's grid.
+    // `Pegged` orders are exempt from the tick check since their resting
+    // price is derived from the opposite best price, not supplied by the
+    // caller; `Market` orders are exempt since their `price` field is
+    // never used for matching (see `crosses`).
+    fn validate_order(&self, order: &Order) -> Result<(), OrderError> {
+        let skips_tick_check = matches!(order.order_type, OrderType::Pegged { .. } | OrderType::Market);
+        if !skips_tick_check && order.price % self.tick_size != 0 {
+            return Err(OrderError::InvalidTick);
         }
+        if order.quantity % self.lot_size != 0 {
+            return Err(OrderError::InvalidLot);
+        }
+        if order.quantity < self.min_size {
+            return Err(OrderError::BelowMinimum);
+        }
+        Ok(())
     }
 
-    fn add_order(&mut self, order: Order) {
+    // Submits an order to the book. `Limit` and `Pegged` orders simply
+    // rest (a `Pegged` order's price is derived first); `Market`,
+    // `ImmediateOrCancel` and `FillOrKill` orders are matched against the
+    // opposite side immediately and never leave a resting remainder.
+    // `now` is used to lazily skip any expired resting orders encountered
+    // while matching, and to re-derive pegged prices afterwards.
+    fn add_order(&mut self, mut order: Order, now: time::SystemTime) -> Result<OrderResult, OrderError> {
+        self.validate_order(&order)?;
+        let order_id = order.id;
+        let result = match order.order_type {
+            OrderType::Limit => {
+                self.rest_order(order);
+                OrderResult {
+                    order_id,
+                    filled_quantity: 0,
+                    status: OrderStatus::Rested,
+                    fills: Vec::new(),
+                }
+            }
+            OrderType::Pegged { offset, .. } => {
+                order.price = match self.derive_pegged_price(order.order_side, offset, now) {
+                    Some(derived) => derived,
+                    // No opposite liquidity to derive from yet: fall back
+                    // to the submitted price, still rounded onto the tick
+                    // grid so it can't rest off-grid like every other
+                    // order type is prevented from doing.
+                    None => self.round_to_tick(order.order_side, order.price),
+                };
+                self.rest_order(order);
+                OrderResult {
+                    order_id,
+                    filled_quantity: 0,
+                    status: OrderStatus::Rested,
+                    fills: Vec::new(),
+                }
+            }
+            OrderType::Market | OrderType::ImmediateOrCancel => {
+                let fills = self.match_incoming(&mut order, now);
+                let filled_quantity = order.quantity - order.remaining;
+                let status = if order.remaining <= 0 {
+                    OrderStatus::FullyFilled
+                } else {
+                    OrderStatus::PartiallyFilled
+                };
+                OrderResult {
+                    order_id,
+                    filled_quantity,
+                    status,
+                    fills,
+                }
+            }
+            OrderType::FillOrKill => {
+                let available = self.available_quantity(order.order_side, order.order_type, order.price, now);
+                if available < order.remaining {
+                    OrderResult {
+                        order_id,
+                        filled_quantity: 0,
+                        status: OrderStatus::Rejected,
+                        fills: Vec::new(),
+                    }
+                } else {
+                    let fills = self.match_incoming(&mut order, now);
+                    OrderResult {
+                        order_id,
+                        filled_quantity: order.quantity - order.remaining,
+                        status: OrderStatus::FullyFilled,
+                        fills,
+                    }
+                }
+            }
+        };
+        self.reprice_pegged_orders(now);
+        Ok(result)
+    }
+
+    // Inserts an order onto its resting heap and into `order_map` without
+    // attempting to match it.
+    fn rest_order(&mut self, order: Order) {
         let new_order_index = OrderIndex {
             id: order.id,
             order_side: order.order_side,
@@ -104,21 +302,427 @@ impl StickerOrderBook {
         self.order_map.insert(order.id, order);
     }
 
-    fn remove_order(&mut self, order_id: i64) {
-        self.order_queue_bid.retain(|index| index.id != order_id);
-        self.order_queue_ask.retain(|index| index.id != order_id);
-        self.order_map.remove_entry(&order_id);
+    // Derives a `Pegged` order's resting price from the current best price
+    // on the opposite side (a bid pegs to the best ask and vice versa),
+    // rounded back onto the book's tick grid. Returns `None` if the
+    // opposite side is empty; `add_order` then rests the order at its
+    // submitted price rounded onto the tick grid, and
+    // `reprice_pegged_orders` leaves it at its current price.
+    fn derive_pegged_price(&mut self, order_side: OrderSide, offset: i64, now: time::SystemTime) -> Option<i64> {
+        let opposite_heap = match order_side {
+            OrderSide::Bid => &mut self.order_queue_ask,
+            OrderSide::Ask => &mut self.order_queue_bid,
+        };
+        let best = Self::peek_valid(opposite_heap, &mut self.order_map, now)?;
+        Some(self.round_to_tick(order_side, best.price + offset))
+    }
+
+    // Rounds `price` onto the book's tick grid, rounding a bid down and an
+    // ask up so a pegged order never ends up more aggressive than its
+    // derived reference plus offset would allow.
+    fn round_to_tick(&self, order_side: OrderSide, price: i64) -> i64 {
+        let remainder = price.rem_euclid(self.tick_size);
+        if remainder == 0 {
+            return price;
+        }
+        match order_side {
+            OrderSide::Bid => price - remainder,
+            OrderSide::Ask => price + (self.tick_size - remainder),
+        }
+    }
+
+    // Removes `order_id`'s `OrderIndex` from its heap and re-inserts it at
+    // `new_price` with `now` as its effective timestamp, updating the
+    // resting `Order` to match. A repriced order has just arrived at this
+    // price level, so it must take a fresh place in time priority rather
+    // than keeping the timestamp it originally rested with — otherwise it
+    // would unfairly jump ahead of orders that have genuinely been resting
+    // at this price all along.
+    fn reindex_order(&mut self, order_id: i64, order_side: OrderSide, new_price: i64, now: time::SystemTime) {
+        match order_side {
+            OrderSide::Bid => self.order_queue_bid.retain(|index| index.id != order_id),
+            OrderSide::Ask => self.order_queue_ask.retain(|index| index.id != order_id),
+        }
+        match self.order_map.get_mut(&order_id) {
+            Some(order) => order.price = new_price,
+            None => return,
+        };
+        let new_index = OrderIndex {
+            id: order_id,
+            price: new_price,
+            timestamp: now,
+            order_side,
+        };
+        match order_side {
+            OrderSide::Bid => self.order_queue_bid.push(new_index),
+            OrderSide::Ask => self.order_queue_ask.push(new_index),
+        }
+    }
+
+    // Re-derives the price of every resting `Pegged` order and re-indexes
+    // any whose derived price has moved since it last rested. Called at the
+    // end of `add_order`, `match_order`, `remove_order`,
+    // `cancel_all_by_user` and `prune_expired`, so pegged orders keep
+    // tracking the spread instead of going stale; popping with
+    // `next_bid_order`/`next_ask_order` does not re-price.
+    fn reprice_pegged_orders(&mut self, now: time::SystemTime) {
+        let mut pegged: Vec<(i64, OrderSide, i64, i64)> = self
+            .order_map
+            .values()
+            .filter_map(|order| match order.order_type {
+                OrderType::Pegged { offset, .. } => Some((order.id, order.order_side, order.price, offset)),
+                _ => None,
+            })
+            .collect();
+        // `order_map` iteration order is unspecified; sort by id so two
+        // orders pegged to opposite sides of each other re-derive in a
+        // deterministic order instead of converging differently run to run.
+        pegged.sort_by_key(|&(order_id, ..)| order_id);
+        for (order_id, order_side, current_price, offset) in pegged {
+            if let Some(new_price) = self.derive_pegged_price(order_side, offset, now) {
+                if new_price != current_price {
+                    self.reindex_order(order_id, order_side, new_price, now);
+                }
+            }
+        }
+    }
+
+    // True if an order on `order_side` with `order_type`/`order_price` would
+    // trade against a resting order at `resting_price`. Market orders cross
+    // at any price; the other types require the usual bid >= ask condition.
+    fn crosses(order_side: OrderSide, order_type: OrderType, order_price: i64, resting_price: i64) -> bool {
+        if order_type == OrderType::Market {
+            return true;
+        }
+        match order_side {
+            OrderSide::Bid => order_price >= resting_price,
+            OrderSide::Ask => order_price <= resting_price,
+        }
+    }
+
+    // Sums the resting quantity on the opposite side that `order` could
+    // trade against, without mutating any state. Used by `FillOrKill` to
+    // decide whether it can execute completely before touching the book.
+    // Expired resting orders are not counted as available, though they are
+    // left in place for the next pop/prune to clean up.
+    fn available_quantity(
+        &self,
+        order_side: OrderSide,
+        order_type: OrderType,
+        order_price: i64,
+        now: time::SystemTime,
+    ) -> i64 {
+        let opposite_heap = match order_side {
+            OrderSide::Bid => &self.order_queue_ask,
+            OrderSide::Ask => &self.order_queue_bid,
+        };
+        opposite_heap
+            .iter()
+            .filter(|index| Self::crosses(order_side, order_type, order_price, index.price))
+            .filter_map(|index| self.order_map.get(&index.id))
+            .filter(|resting| !Self::order_expired(resting, now))
+            .map(|resting| resting.remaining)
+            .sum()
+    }
+
+    // True once `now` has reached the order's `expires_at` (GoodTillCancel
+    // orders, with `expires_at: None`, never expire).
+    fn order_expired(order: &Order, now: time::SystemTime) -> bool {
+        order.expires_at.map_or(false, |expires_at| now >= expires_at)
+    }
+
+    // Pops the next non-expired index off `heap`, lazily dropping any
+    // expired orders it encounters (and their `order_map` entries) along
+    // the way.
+    fn pop_valid(
+        heap: &mut BinaryHeap<OrderIndex>,
+        order_map: &mut HashMap<i64, Order>,
+        now: time::SystemTime,
+    ) -> Option<OrderIndex> {
+        while let Some(index) = heap.pop() {
+            match order_map.get(&index.id) {
+                Some(order) if Self::order_expired(order, now) => {
+                    order_map.remove(&index.id);
+                }
+                _ => return Some(index),
+            }
+        }
+        None
     }
 
-    fn next_bid_order(&mut self) -> Option<OrderIndex> {
-        return self.order_queue_bid.pop();
+    // Same lazy-expiry skipping as `pop_valid`, but leaves the valid index
+    // on top of the heap instead of removing it.
+    fn peek_valid(
+        heap: &mut BinaryHeap<OrderIndex>,
+        order_map: &mut HashMap<i64, Order>,
+        now: time::SystemTime,
+    ) -> Option<OrderIndex> {
+        loop {
+            let index = heap.peek()?.clone();
+            match order_map.get(&index.id) {
+                Some(order) if Self::order_expired(order, now) => {
+                    heap.pop();
+                    order_map.remove(&index.id);
+                }
+                _ => return Some(index),
+            }
+        }
     }
-    fn next_ask_order(&mut self) -> Option<OrderIndex> {
-        return self.order_queue_ask.pop();
+
+    // Drains both heaps and drops every order that has expired as of `now`,
+    // rebuilding each heap from its survivors.
+    fn prune_expired(&mut self, now: time::SystemTime) {
+        let order_map = &mut self.order_map;
+        self.order_queue_bid = self
+            .order_queue_bid
+            .drain()
+            .filter(|index| match order_map.get(&index.id) {
+                Some(order) if Self::order_expired(order, now) => {
+                    order_map.remove(&index.id);
+                    false
+                }
+                _ => true,
+            })
+            .collect();
+        let order_map = &mut self.order_map;
+        self.order_queue_ask = self
+            .order_queue_ask
+            .drain()
+            .filter(|index| match order_map.get(&index.id) {
+                Some(order) if Self::order_expired(order, now) => {
+                    order_map.remove(&index.id);
+                    false
+                }
+                _ => true,
+            })
+            .collect();
+        self.reprice_pegged_orders(now);
     }
-    fn match_order(&mut self) {
-        // pop ask first, pop until you get a different price
-        // pop bid, see if number works
+
+    // Matches `order` (the taker) against the opposite side's resting
+    // orders until it is filled or no more crossing liquidity remains.
+    // `order` is never inserted into the book; the caller decides what, if
+    // anything, to do with any quantity left over.
+    fn match_incoming(&mut self, order: &mut Order, now: time::SystemTime) -> Vec<Fill> {
+        let mut fills = Vec::new();
+        loop {
+            if order.remaining <= 0 {
+                break;
+            }
+            let opposite_heap = match order.order_side {
+                OrderSide::Bid => &mut self.order_queue_ask,
+                OrderSide::Ask => &mut self.order_queue_bid,
+            };
+            let best_opposite = Self::peek_valid(opposite_heap, &mut self.order_map, now);
+            let crosses = match best_opposite {
+                Some(index) => Self::crosses(order.order_side, order.order_type, order.price, index.price),
+                None => false,
+            };
+            if !crosses {
+                break;
+            }
+
+            let opposite_heap = match order.order_side {
+                OrderSide::Bid => &mut self.order_queue_ask,
+                OrderSide::Ask => &mut self.order_queue_bid,
+            };
+            let resting_index = Self::pop_valid(opposite_heap, &mut self.order_map, now).unwrap();
+            let resting_order = self
+                .order_map
+                .get(&resting_index.id)
+                .expect("resting index without matching order");
+            let trade_price = resting_order.price;
+            let trade_quantity = order.remaining.min(resting_order.remaining);
+            let resting_creator = resting_order.creator_user_id.clone();
+
+            fills.push(Fill {
+                maker_id: resting_index.id,
+                taker_id: order.id,
+                price: trade_price,
+                quantity: trade_quantity,
+                timestamp: time::SystemTime::now(),
+            });
+
+            order.remaining -= trade_quantity;
+            order.fulfiller_user_id = Some(resting_creator.clone());
+            order.is_fulfilled = order.remaining <= 0;
+            let resting_done = self.settle_order(resting_index.id, trade_quantity, order.creator_user_id.clone());
+            if !resting_done {
+                match order.order_side {
+                    OrderSide::Bid => self.order_queue_ask.push(resting_index),
+                    OrderSide::Ask => self.order_queue_bid.push(resting_index),
+                }
+            }
+        }
+        fills
+    }
+
+    // Cancels a single order. Returns true if `order_id` was actually
+    // resting and got removed, false if there was nothing to cancel.
+    // `now` re-prices any pegged orders left behind whose reference price
+    // this cancellation may have moved.
+    fn remove_order(&mut self, order_id: i64, now: time::SystemTime) -> bool {
+        let removed = self.order_map.remove(&order_id).is_some();
+        if removed {
+            self.order_queue_bid.retain(|index| index.id != order_id);
+            self.order_queue_ask.retain(|index| index.id != order_id);
+            self.reprice_pegged_orders(now);
+        }
+        removed
+    }
+
+    // Cancels every resting order created by `user_id`, returning the ids
+    // that were cancelled. `now` re-prices any pegged orders left behind
+    // whose reference price this cancellation may have moved.
+    fn cancel_all_by_user(&mut self, user_id: &str, now: time::SystemTime) -> Vec<i64> {
+        let cancelled_ids: Vec<i64> = self
+            .order_map
+            .iter()
+            .filter(|(_, order)| order.creator_user_id == user_id)
+            .map(|(id, _)| *id)
+            .collect();
+        for id in &cancelled_ids {
+            self.order_map.remove(id);
+        }
+        let cancelled: HashSet<i64> = cancelled_ids.iter().copied().collect();
+        self.order_queue_bid.retain(|index| !cancelled.contains(&index.id));
+        self.order_queue_ask.retain(|index| !cancelled.contains(&index.id));
+        self.reprice_pegged_orders(now);
+        cancelled_ids
+    }
+
+    fn next_bid_order(&mut self, now: time::SystemTime) -> Option<OrderIndex> {
+        Self::pop_valid(&mut self.order_queue_bid, &mut self.order_map, now)
+    }
+    fn next_ask_order(&mut self, now: time::SystemTime) -> Option<OrderIndex> {
+        Self::pop_valid(&mut self.order_queue_ask, &mut self.order_map, now)
+    }
+
+    // Aggregated L2 market depth: the top `levels` price levels resting on
+    // each side, without mutating either heap. Built by grouping
+    // `order_map` by price rather than draining the heaps. `now` excludes
+    // orders that have already expired per their `GoodTillTime`, the same
+    // as matching would.
+    fn depth(&self, levels: usize, now: time::SystemTime) -> BookDepth {
+        let mut bid_levels: BTreeMap<i64, (i64, usize)> = BTreeMap::new();
+        let mut ask_levels: BTreeMap<i64, (i64, usize)> = BTreeMap::new();
+        for order in self.order_map.values().filter(|order| !Self::order_expired(order, now)) {
+            let level = match order.order_side {
+                OrderSide::Bid => bid_levels.entry(order.price),
+                OrderSide::Ask => ask_levels.entry(order.price),
+            }
+            .or_insert((0, 0));
+            level.0 += order.remaining;
+            level.1 += 1;
+        }
+
+        let to_price_level = |(price, (total_quantity, order_count))| PriceLevel {
+            price,
+            total_quantity,
+            order_count,
+        };
+        BookDepth {
+            bids: bid_levels
+                .into_iter()
+                .rev()
+                .take(levels)
+                .map(to_price_level)
+                .collect(),
+            asks: ask_levels
+                .into_iter()
+                .take(levels)
+                .map(to_price_level)
+                .collect(),
+        }
+    }
+
+    // Price-time priority matching: crosses the book while the best bid
+    // is at or above the best ask, trading at the resting (maker) order's
+    // price. Partially-filled orders are pushed back onto their heap so
+    // they keep their place for the next crossing pass. Expired orders
+    // encountered along the way are dropped instead of being matched.
+    fn match_order(&mut self, now: time::SystemTime) -> Vec<Fill> {
+        let mut fills = Vec::new();
+        loop {
+            let crosses = match (
+                Self::peek_valid(&mut self.order_queue_bid, &mut self.order_map, now),
+                Self::peek_valid(&mut self.order_queue_ask, &mut self.order_map, now),
+            ) {
+                (Some(bid), Some(ask)) => bid.price >= ask.price,
+                _ => false,
+            };
+            if !crosses {
+                break;
+            }
+
+            let bid_index = Self::pop_valid(&mut self.order_queue_bid, &mut self.order_map, now).unwrap();
+            let ask_index = Self::pop_valid(&mut self.order_queue_ask, &mut self.order_map, now).unwrap();
+
+            let bid_order = self
+                .order_map
+                .get(&bid_index.id)
+                .expect("bid index without matching order");
+            let ask_order = self
+                .order_map
+                .get(&ask_index.id)
+                .expect("ask index without matching order");
+
+            let trade_quantity = bid_order.remaining.min(ask_order.remaining);
+            let maker_is_bid = bid_index.timestamp <= ask_index.timestamp;
+            let trade_price = if maker_is_bid {
+                bid_order.price
+            } else {
+                ask_order.price
+            };
+            let bid_creator = bid_order.creator_user_id.clone();
+            let ask_creator = ask_order.creator_user_id.clone();
+
+            let (maker_id, taker_id) = if maker_is_bid {
+                (bid_index.id, ask_index.id)
+            } else {
+                (ask_index.id, bid_index.id)
+            };
+            fills.push(Fill {
+                maker_id,
+                taker_id,
+                price: trade_price,
+                quantity: trade_quantity,
+                timestamp: time::SystemTime::now(),
+            });
+
+            if !self.settle_order(bid_index.id, trade_quantity, ask_creator) {
+                self.order_queue_bid.push(bid_index);
+            }
+            if !self.settle_order(ask_index.id, trade_quantity, bid_creator) {
+                self.order_queue_ask.push(ask_index);
+            }
+        }
+        self.reprice_pegged_orders(now);
+        fills
+    }
+
+    // Applies a trade to a resting order. Returns true once the order is
+    // fully filled (and has been dropped from `order_map`), false if it
+    // still has quantity remaining and should stay resting.
+    fn settle_order(&mut self, order_id: i64, quantity: i64, counterparty_user_id: String) -> bool {
+        let remaining = {
+            let order = self
+                .order_map
+                .get_mut(&order_id)
+                .expect("order missing during settlement");
+            order.remaining -= quantity;
+            if order.remaining <= 0 {
+                order.is_fulfilled = true;
+                order.fulfiller_user_id = Some(counterparty_user_id);
+            }
+            order.remaining
+        };
+        if remaining <= 0 {
+            self.order_map.remove(&order_id);
+            true
+        } else {
+            false
+        }
     }
 }
 
@@ -129,7 +733,37 @@ mod tests {
         id: i64,
         price: i64,
         created_at: SystemTime,
-        order_type: OrderSide,
+        order_side: OrderSide,
+    ) -> Order {
+        _create_test_order_with_quantity(id, price, 1, created_at, order_side)
+    }
+    fn _create_test_order_with_quantity(
+        id: i64,
+        price: i64,
+        quantity: i64,
+        created_at: SystemTime,
+        order_side: OrderSide,
+    ) -> Order {
+        _create_test_order_with_type(id, price, quantity, created_at, order_side, OrderType::Limit)
+    }
+    fn _create_test_order_with_type(
+        id: i64,
+        price: i64,
+        quantity: i64,
+        created_at: SystemTime,
+        order_side: OrderSide,
+        order_type: OrderType,
+    ) -> Order {
+        _create_test_order_with_expiry(id, price, quantity, created_at, order_side, order_type, None)
+    }
+    fn _create_test_order_with_expiry(
+        id: i64,
+        price: i64,
+        quantity: i64,
+        created_at: SystemTime,
+        order_side: OrderSide,
+        order_type: OrderType,
+        expires_at: Option<SystemTime>,
     ) -> Order {
         Order {
             id: id,
@@ -138,8 +772,12 @@ mod tests {
             fulfiller_user_id: None,
             is_fulfilled: false,
             price: price,
-            order_side: order_type,
+            quantity: quantity,
+            remaining: quantity,
+            order_side: order_side,
+            order_type: order_type,
             created_at: created_at,
+            expires_at: expires_at,
         }
     }
     #[test]
@@ -149,12 +787,12 @@ mod tests {
         assert_ne!(id1, id2);
         let order1: Order = _create_test_order(id1, 25, SystemTime::now(), OrderSide::Ask);
         let order2: Order = _create_test_order(id2, 15, SystemTime::now(), OrderSide::Ask);
-        let mut order_book = StickerOrderBook::new();
-        order_book.add_order(order1);
-        order_book.add_order(order2);
-        let res1 = order_book.next_ask_order();
-        let res2 = order_book.next_ask_order();
-        let res3 = order_book.next_ask_order();
+        let mut order_book = StickerOrderBook::new(1, 1, 1);
+        order_book.add_order(order1, SystemTime::now()).unwrap();
+        order_book.add_order(order2, SystemTime::now()).unwrap();
+        let res1 = order_book.next_ask_order(SystemTime::now());
+        let res2 = order_book.next_ask_order(SystemTime::now());
+        let res3 = order_book.next_ask_order(SystemTime::now());
         assert!(res1.is_some());
         assert!(res2.is_some());
         assert_eq!(res3, None);
@@ -179,12 +817,12 @@ mod tests {
         let order1: Order = _create_test_order(id1, 15, SystemTime::now(), OrderSide::Ask);
         let order2: Order = _create_test_order(id2, 15, future_time, OrderSide::Ask);
 
-        let mut order_book = StickerOrderBook::new();
-        order_book.add_order(order1);
-        order_book.add_order(order2);
-        let res1 = order_book.next_ask_order();
-        let res2 = order_book.next_ask_order();
-        let res3 = order_book.next_ask_order();
+        let mut order_book = StickerOrderBook::new(1, 1, 1);
+        order_book.add_order(order1, SystemTime::now()).unwrap();
+        order_book.add_order(order2, SystemTime::now()).unwrap();
+        let res1 = order_book.next_ask_order(SystemTime::now());
+        let res2 = order_book.next_ask_order(SystemTime::now());
+        let res3 = order_book.next_ask_order(SystemTime::now());
         assert!(res1.is_some());
         assert!(res2.is_some());
         assert_eq!(res3, None);
@@ -207,12 +845,12 @@ mod tests {
         let order1: Order = _create_test_order(id1, 25, SystemTime::now(), OrderSide::Bid);
         let order2: Order = _create_test_order(id2, 15, SystemTime::now(), OrderSide::Bid);
 
-        let mut order_book = StickerOrderBook::new();
-        order_book.add_order(order1);
-        order_book.add_order(order2);
-        let res1 = order_book.next_bid_order();
-        let res2 = order_book.next_bid_order();
-        let res3 = order_book.next_bid_order();
+        let mut order_book = StickerOrderBook::new(1, 1, 1);
+        order_book.add_order(order1, SystemTime::now()).unwrap();
+        order_book.add_order(order2, SystemTime::now()).unwrap();
+        let res1 = order_book.next_bid_order(SystemTime::now());
+        let res2 = order_book.next_bid_order(SystemTime::now());
+        let res3 = order_book.next_bid_order(SystemTime::now());
         assert!(res1.is_some());
         assert!(res2.is_some());
         assert_eq!(res3, None);
@@ -236,12 +874,12 @@ mod tests {
         let future_time = now + ten_minutes;
         let order1: Order = _create_test_order(id1, 15, SystemTime::now(), OrderSide::Bid);
         let order2: Order = _create_test_order(id2, 15, future_time, OrderSide::Bid);
-        let mut order_book = StickerOrderBook::new();
-        order_book.add_order(order1);
-        order_book.add_order(order2);
-        let res1 = order_book.next_bid_order();
-        let res2 = order_book.next_bid_order();
-        let res3 = order_book.next_bid_order();
+        let mut order_book = StickerOrderBook::new(1, 1, 1);
+        order_book.add_order(order1, SystemTime::now()).unwrap();
+        order_book.add_order(order2, SystemTime::now()).unwrap();
+        let res1 = order_book.next_bid_order(SystemTime::now());
+        let res2 = order_book.next_bid_order(SystemTime::now());
+        let res3 = order_book.next_bid_order(SystemTime::now());
         assert!(res1.is_some());
         assert!(res2.is_some());
         assert_eq!(res3, None);
@@ -256,4 +894,514 @@ mod tests {
             panic!("second_option should not be null");
         }
     }
+    #[test]
+    fn match_order_fully_fills_both_sides_at_maker_price() {
+        let bid = _create_test_order_with_quantity(1, 20, 10, SystemTime::now(), OrderSide::Bid);
+        let ask =
+            _create_test_order_with_quantity(2, 15, 10, SystemTime::now(), OrderSide::Ask);
+
+        let mut order_book = StickerOrderBook::new(1, 1, 1);
+        order_book.add_order(ask, SystemTime::now()).unwrap();
+        order_book.add_order(bid, SystemTime::now()).unwrap();
+        let fills = order_book.match_order(SystemTime::now());
+
+        assert_eq!(fills.len(), 1);
+        assert_eq!(fills[0].price, 20);
+        assert_eq!(fills[0].quantity, 10);
+        assert!(order_book.order_map.is_empty());
+        assert!(order_book.next_bid_order(SystemTime::now()).is_none());
+        assert!(order_book.next_ask_order(SystemTime::now()).is_none());
+    }
+    #[test]
+    fn match_order_leaves_partial_remainder_resting() {
+        let bid = _create_test_order_with_quantity(1, 20, 5, SystemTime::now(), OrderSide::Bid);
+        let ask = _create_test_order_with_quantity(2, 20, 8, SystemTime::now(), OrderSide::Ask);
+
+        let mut order_book = StickerOrderBook::new(1, 1, 1);
+        order_book.add_order(ask, SystemTime::now()).unwrap();
+        order_book.add_order(bid, SystemTime::now()).unwrap();
+        let fills = order_book.match_order(SystemTime::now());
+
+        assert_eq!(fills.len(), 1);
+        assert_eq!(fills[0].quantity, 5);
+        let remaining_ask = order_book.order_map.get(&2).expect("ask should still rest");
+        assert_eq!(remaining_ask.remaining, 3);
+        assert!(!remaining_ask.is_fulfilled);
+        assert!(!order_book.order_map.contains_key(&1));
+    }
+    #[test]
+    fn match_order_does_nothing_when_no_price_crosses() {
+        let bid = _create_test_order_with_quantity(1, 10, 5, SystemTime::now(), OrderSide::Bid);
+        let ask = _create_test_order_with_quantity(2, 15, 5, SystemTime::now(), OrderSide::Ask);
+
+        let mut order_book = StickerOrderBook::new(1, 1, 1);
+        order_book.add_order(ask, SystemTime::now()).unwrap();
+        order_book.add_order(bid, SystemTime::now()).unwrap();
+        let fills = order_book.match_order(SystemTime::now());
+
+        assert!(fills.is_empty());
+        assert_eq!(order_book.order_map.len(), 2);
+    }
+    #[test]
+    fn market_order_sweeps_book_without_resting() {
+        let resting_ask =
+            _create_test_order_with_quantity(1, 20, 5, SystemTime::now(), OrderSide::Ask);
+        let mut order_book = StickerOrderBook::new(1, 1, 1);
+        order_book.add_order(resting_ask, SystemTime::now()).unwrap();
+
+        let market_bid = _create_test_order_with_type(
+            2,
+            0,
+            5,
+            SystemTime::now(),
+            OrderSide::Bid,
+            OrderType::Market,
+        );
+        let result = order_book.add_order(market_bid, SystemTime::now()).unwrap();
+
+        assert_eq!(result.status, OrderStatus::FullyFilled);
+        assert_eq!(result.filled_quantity, 5);
+        assert_eq!(result.fills[0].price, 20);
+        assert!(order_book.order_map.is_empty());
+        assert!(order_book.next_bid_order(SystemTime::now()).is_none());
+    }
+    #[test]
+    fn immediate_or_cancel_discards_unfilled_remainder() {
+        let resting_ask =
+            _create_test_order_with_quantity(1, 20, 3, SystemTime::now(), OrderSide::Ask);
+        let mut order_book = StickerOrderBook::new(1, 1, 1);
+        order_book.add_order(resting_ask, SystemTime::now()).unwrap();
+
+        let ioc_bid = _create_test_order_with_type(
+            2,
+            20,
+            5,
+            SystemTime::now(),
+            OrderSide::Bid,
+            OrderType::ImmediateOrCancel,
+        );
+        let result = order_book.add_order(ioc_bid, SystemTime::now()).unwrap();
+
+        assert_eq!(result.status, OrderStatus::PartiallyFilled);
+        assert_eq!(result.filled_quantity, 3);
+        assert!(order_book.next_bid_order(SystemTime::now()).is_none());
+        assert!(order_book.order_map.is_empty());
+    }
+    #[test]
+    fn fill_or_kill_rejects_when_book_cannot_cover_full_quantity() {
+        let resting_ask =
+            _create_test_order_with_quantity(1, 20, 3, SystemTime::now(), OrderSide::Ask);
+        let mut order_book = StickerOrderBook::new(1, 1, 1);
+        order_book.add_order(resting_ask, SystemTime::now()).unwrap();
+
+        let fok_bid = _create_test_order_with_type(
+            2,
+            20,
+            5,
+            SystemTime::now(),
+            OrderSide::Bid,
+            OrderType::FillOrKill,
+        );
+        let result = order_book.add_order(fok_bid, SystemTime::now()).unwrap();
+
+        assert_eq!(result.status, OrderStatus::Rejected);
+        assert_eq!(result.filled_quantity, 0);
+        assert!(result.fills.is_empty());
+        let resting = order_book.order_map.get(&1).expect("resting ask untouched");
+        assert_eq!(resting.remaining, 3);
+    }
+    #[test]
+    fn fill_or_kill_executes_completely_when_book_can_cover_it() {
+        let resting_ask =
+            _create_test_order_with_quantity(1, 20, 8, SystemTime::now(), OrderSide::Ask);
+        let mut order_book = StickerOrderBook::new(1, 1, 1);
+        order_book.add_order(resting_ask, SystemTime::now()).unwrap();
+
+        let fok_bid = _create_test_order_with_type(
+            2,
+            20,
+            5,
+            SystemTime::now(),
+            OrderSide::Bid,
+            OrderType::FillOrKill,
+        );
+        let result = order_book.add_order(fok_bid, SystemTime::now()).unwrap();
+
+        assert_eq!(result.status, OrderStatus::FullyFilled);
+        assert_eq!(result.filled_quantity, 5);
+        let resting = order_book.order_map.get(&1).expect("resting ask should remain partial");
+        assert_eq!(resting.remaining, 3);
+    }
+    #[test]
+    fn add_order_rejects_price_off_tick() {
+        let mut order_book = StickerOrderBook::new(5, 1, 1);
+        let order = _create_test_order_with_quantity(1, 12, 1, SystemTime::now(), OrderSide::Bid);
+        let result = order_book.add_order(order, SystemTime::now());
+        assert_eq!(result, Err(OrderError::InvalidTick));
+    }
+    #[test]
+    fn add_order_rejects_quantity_off_lot() {
+        let mut order_book = StickerOrderBook::new(1, 5, 1);
+        let order = _create_test_order_with_quantity(1, 10, 7, SystemTime::now(), OrderSide::Bid);
+        let result = order_book.add_order(order, SystemTime::now());
+        assert_eq!(result, Err(OrderError::InvalidLot));
+    }
+    #[test]
+    fn add_order_rejects_quantity_below_minimum() {
+        let mut order_book = StickerOrderBook::new(1, 1, 10);
+        let order = _create_test_order_with_quantity(1, 10, 5, SystemTime::now(), OrderSide::Bid);
+        let result = order_book.add_order(order, SystemTime::now());
+        assert_eq!(result, Err(OrderError::BelowMinimum));
+    }
+    #[test]
+    fn add_order_accepts_valid_tick_lot_and_size() {
+        let mut order_book = StickerOrderBook::new(5, 5, 10);
+        let order = _create_test_order_with_quantity(1, 15, 10, SystemTime::now(), OrderSide::Bid);
+        let result = order_book.add_order(order, SystemTime::now());
+        assert!(result.is_ok());
+    }
+    #[test]
+    #[should_panic(expected = "tick_size must be positive")]
+    fn new_rejects_non_positive_tick_size() {
+        StickerOrderBook::new(0, 1, 1);
+    }
+    #[test]
+    #[should_panic(expected = "lot_size must be positive")]
+    fn new_rejects_non_positive_lot_size() {
+        StickerOrderBook::new(1, 0, 1);
+    }
+    #[test]
+    fn market_order_is_exempt_from_tick_check() {
+        let mut order_book = StickerOrderBook::new(5, 1, 1);
+        let resting_ask =
+            _create_test_order_with_quantity(1, 20, 5, SystemTime::now(), OrderSide::Ask);
+        order_book.add_order(resting_ask, SystemTime::now()).unwrap();
+
+        let market_bid = _create_test_order_with_type(
+            2,
+            3,
+            5,
+            SystemTime::now(),
+            OrderSide::Bid,
+            OrderType::Market,
+        );
+        let result = order_book.add_order(market_bid, SystemTime::now()).unwrap();
+
+        assert_eq!(result.status, OrderStatus::FullyFilled);
+        assert_eq!(result.filled_quantity, 5);
+    }
+    #[test]
+    fn depth_aggregates_quantity_per_price_level() {
+        let mut order_book = StickerOrderBook::new(1, 1, 1);
+        order_book
+            .add_order(
+                _create_test_order_with_quantity(1, 20, 5, SystemTime::now(), OrderSide::Bid),
+                SystemTime::now(),
+            )
+            .unwrap();
+        order_book
+            .add_order(
+                _create_test_order_with_quantity(2, 20, 3, SystemTime::now(), OrderSide::Bid),
+                SystemTime::now(),
+            )
+            .unwrap();
+        order_book
+            .add_order(
+                _create_test_order_with_quantity(3, 18, 4, SystemTime::now(), OrderSide::Bid),
+                SystemTime::now(),
+            )
+            .unwrap();
+        order_book
+            .add_order(
+                _create_test_order_with_quantity(4, 25, 6, SystemTime::now(), OrderSide::Ask),
+                SystemTime::now(),
+            )
+            .unwrap();
+
+        let depth = order_book.depth(10, SystemTime::now());
+
+        assert_eq!(
+            depth.bids,
+            vec![
+                PriceLevel { price: 20, total_quantity: 8, order_count: 2 },
+                PriceLevel { price: 18, total_quantity: 4, order_count: 1 },
+            ]
+        );
+        assert_eq!(
+            depth.asks,
+            vec![PriceLevel { price: 25, total_quantity: 6, order_count: 1 }]
+        );
+    }
+    #[test]
+    fn depth_truncates_to_requested_levels() {
+        let mut order_book = StickerOrderBook::new(1, 1, 1);
+        for (id, price) in [(1, 10), (2, 11), (3, 12)] {
+            order_book
+                .add_order(
+                    _create_test_order_with_quantity(id, price, 1, SystemTime::now(), OrderSide::Ask),
+                    SystemTime::now(),
+                )
+                .unwrap();
+        }
+
+        let depth = order_book.depth(2, SystemTime::now());
+
+        assert_eq!(depth.asks.len(), 2);
+        assert_eq!(depth.asks[0].price, 10);
+        assert_eq!(depth.asks[1].price, 11);
+    }
+    #[test]
+    fn depth_excludes_expired_orders() {
+        let now = SystemTime::now();
+        let expired = _create_test_order_with_expiry(
+            1,
+            20,
+            5,
+            now,
+            OrderSide::Ask,
+            OrderType::Limit,
+            Some(now),
+        );
+        let live = _create_test_order_with_expiry(2, 22, 3, now, OrderSide::Ask, OrderType::Limit, None);
+        let mut order_book = StickerOrderBook::new(1, 1, 1);
+        order_book.add_order(expired, now).unwrap();
+        order_book.add_order(live, now).unwrap();
+
+        let later = now + Duration::from_secs(1);
+        let depth = order_book.depth(10, later);
+
+        assert_eq!(depth.asks, vec![PriceLevel { price: 22, total_quantity: 3, order_count: 1 }]);
+    }
+    #[test]
+    fn next_ask_order_skips_expired_orders() {
+        let now = SystemTime::now();
+        let expired = _create_test_order_with_expiry(
+            1,
+            10,
+            1,
+            now,
+            OrderSide::Ask,
+            OrderType::Limit,
+            Some(now),
+        );
+        let live = _create_test_order_with_expiry(
+            2,
+            20,
+            1,
+            now,
+            OrderSide::Ask,
+            OrderType::Limit,
+            None,
+        );
+        let mut order_book = StickerOrderBook::new(1, 1, 1);
+        order_book.add_order(expired, now).unwrap();
+        order_book.add_order(live, now).unwrap();
+
+        let later = now + Duration::from_secs(1);
+        let next = order_book.next_ask_order(later);
+
+        assert_eq!(next.map(|index| index.id), Some(2));
+        assert!(!order_book.order_map.contains_key(&1));
+    }
+    #[test]
+    fn match_order_skips_an_expired_resting_order() {
+        let now = SystemTime::now();
+        let expired_bid =
+            _create_test_order_with_expiry(1, 20, 5, now, OrderSide::Bid, OrderType::Limit, Some(now));
+        let live_bid =
+            _create_test_order_with_expiry(2, 20, 5, now, OrderSide::Bid, OrderType::Limit, None);
+        let ask = _create_test_order_with_quantity(3, 20, 5, now, OrderSide::Ask);
+
+        let mut order_book = StickerOrderBook::new(1, 1, 1);
+        order_book.add_order(expired_bid, now).unwrap();
+        order_book.add_order(live_bid, now).unwrap();
+        order_book.add_order(ask, now).unwrap();
+
+        let later = now + Duration::from_secs(1);
+        let fills = order_book.match_order(later);
+
+        assert_eq!(fills.len(), 1);
+        assert_eq!(fills[0].maker_id, 2);
+        assert!(!order_book.order_map.contains_key(&1));
+    }
+    #[test]
+    fn prune_expired_removes_stale_orders_from_both_sides() {
+        let now = SystemTime::now();
+        let expired_bid =
+            _create_test_order_with_expiry(1, 20, 5, now, OrderSide::Bid, OrderType::Limit, Some(now));
+        let expired_ask =
+            _create_test_order_with_expiry(2, 25, 5, now, OrderSide::Ask, OrderType::Limit, Some(now));
+        let live_bid = _create_test_order_with_expiry(3, 15, 5, now, OrderSide::Bid, OrderType::Limit, None);
+
+        let mut order_book = StickerOrderBook::new(1, 1, 1);
+        order_book.add_order(expired_bid, now).unwrap();
+        order_book.add_order(expired_ask, now).unwrap();
+        order_book.add_order(live_bid, now).unwrap();
+
+        let later = now + Duration::from_secs(1);
+        order_book.prune_expired(later);
+
+        assert_eq!(order_book.order_map.len(), 1);
+        assert!(order_book.order_map.contains_key(&3));
+    }
+    #[test]
+    fn remove_order_reports_whether_it_found_something() {
+        let order = _create_test_order(1, 10, SystemTime::now(), OrderSide::Bid);
+        let mut order_book = StickerOrderBook::new(1, 1, 1);
+        order_book.add_order(order, SystemTime::now()).unwrap();
+
+        assert!(order_book.remove_order(1, SystemTime::now()));
+        assert!(!order_book.remove_order(1, SystemTime::now()));
+        assert!(order_book.order_map.is_empty());
+    }
+    #[test]
+    fn cancel_all_by_user_removes_only_that_users_orders() {
+        let mut alice_order = _create_test_order(1, 10, SystemTime::now(), OrderSide::Bid);
+        alice_order.creator_user_id = "alice".to_string();
+        let mut alice_order2 = _create_test_order(2, 12, SystemTime::now(), OrderSide::Ask);
+        alice_order2.creator_user_id = "alice".to_string();
+        let mut bob_order = _create_test_order(3, 11, SystemTime::now(), OrderSide::Bid);
+        bob_order.creator_user_id = "bob".to_string();
+
+        let mut order_book = StickerOrderBook::new(1, 1, 1);
+        order_book.add_order(alice_order, SystemTime::now()).unwrap();
+        order_book.add_order(alice_order2, SystemTime::now()).unwrap();
+        order_book.add_order(bob_order, SystemTime::now()).unwrap();
+
+        let mut cancelled = order_book.cancel_all_by_user("alice", SystemTime::now());
+        cancelled.sort();
+
+        assert_eq!(cancelled, vec![1, 2]);
+        assert_eq!(order_book.order_map.len(), 1);
+        assert!(order_book.order_map.contains_key(&3));
+    }
+    #[test]
+    fn pegged_order_derives_price_from_opposite_best_on_rest() {
+        let resting_ask = _create_test_order_with_quantity(1, 20, 5, SystemTime::now(), OrderSide::Ask);
+        let mut order_book = StickerOrderBook::new(1, 1, 1);
+        order_book.add_order(resting_ask, SystemTime::now()).unwrap();
+
+        let pegged_bid = _create_test_order_with_type(
+            2,
+            0,
+            5,
+            SystemTime::now(),
+            OrderSide::Bid,
+            OrderType::Pegged { reference: PegReference::Best, offset: -2 },
+        );
+        order_book.add_order(pegged_bid, SystemTime::now()).unwrap();
+
+        let resting = order_book.order_map.get(&2).expect("pegged order should rest");
+        assert_eq!(resting.price, 18);
+    }
+    #[test]
+    fn pegged_order_with_no_opposite_liquidity_keeps_its_submitted_price() {
+        let mut order_book = StickerOrderBook::new(1, 1, 1);
+        let pegged_bid = _create_test_order_with_type(
+            1,
+            7,
+            5,
+            SystemTime::now(),
+            OrderSide::Bid,
+            OrderType::Pegged { reference: PegReference::Best, offset: -2 },
+        );
+        order_book.add_order(pegged_bid, SystemTime::now()).unwrap();
+
+        let resting = order_book.order_map.get(&1).expect("pegged order should rest");
+        assert_eq!(resting.price, 7);
+    }
+    #[test]
+    fn pegged_order_with_no_opposite_liquidity_still_rounds_onto_the_tick_grid() {
+        let mut order_book = StickerOrderBook::new(5, 1, 1);
+        let pegged_bid = _create_test_order_with_type(
+            1,
+            7,
+            5,
+            SystemTime::now(),
+            OrderSide::Bid,
+            OrderType::Pegged { reference: PegReference::Best, offset: -2 },
+        );
+        order_book.add_order(pegged_bid, SystemTime::now()).unwrap();
+
+        let resting = order_book.order_map.get(&1).expect("pegged order should rest");
+        assert_eq!(resting.price, 5);
+    }
+    #[test]
+    fn pegged_order_reprices_when_opposite_best_improves() {
+        let resting_ask = _create_test_order_with_quantity(1, 20, 5, SystemTime::now(), OrderSide::Ask);
+        let mut order_book = StickerOrderBook::new(1, 1, 1);
+        order_book.add_order(resting_ask, SystemTime::now()).unwrap();
+
+        let pegged_bid = _create_test_order_with_type(
+            2,
+            0,
+            5,
+            SystemTime::now(),
+            OrderSide::Bid,
+            OrderType::Pegged { reference: PegReference::Best, offset: -2 },
+        );
+        order_book.add_order(pegged_bid, SystemTime::now()).unwrap();
+        assert_eq!(order_book.order_map.get(&2).unwrap().price, 18);
+
+        let better_ask = _create_test_order_with_quantity(3, 16, 5, SystemTime::now(), OrderSide::Ask);
+        order_book.add_order(better_ask, SystemTime::now()).unwrap();
+
+        let repriced = order_book.order_map.get(&2).expect("pegged order still rests");
+        assert_eq!(repriced.price, 14);
+    }
+    #[test]
+    fn pegged_order_price_is_rounded_back_onto_the_tick_grid() {
+        let resting_ask = _create_test_order_with_quantity(1, 20, 5, SystemTime::now(), OrderSide::Ask);
+        let mut order_book = StickerOrderBook::new(5, 1, 1);
+        order_book.add_order(resting_ask, SystemTime::now()).unwrap();
+
+        let pegged_bid = _create_test_order_with_type(
+            2,
+            0,
+            5,
+            SystemTime::now(),
+            OrderSide::Bid,
+            OrderType::Pegged { reference: PegReference::Best, offset: -3 },
+        );
+        order_book.add_order(pegged_bid, SystemTime::now()).unwrap();
+
+        let resting = order_book.order_map.get(&2).expect("pegged order should rest");
+        assert_eq!(resting.price, 15);
+    }
+    #[test]
+    fn repriced_pegged_order_loses_time_priority_to_orders_already_resting_at_its_new_price() {
+        let t0 = SystemTime::now();
+        let t2 = t0 + Duration::from_secs(10);
+        let t3 = t0 + Duration::from_secs(20);
+
+        let resting_ask = _create_test_order_with_quantity(1, 22, 5, t0, OrderSide::Ask);
+        let mut order_book = StickerOrderBook::new(1, 1, 1);
+        order_book.add_order(resting_ask, t0).unwrap();
+
+        let pegged_bid = _create_test_order_with_type(
+            2,
+            0,
+            5,
+            t0,
+            OrderSide::Bid,
+            OrderType::Pegged { reference: PegReference::Best, offset: -2 },
+        );
+        order_book.add_order(pegged_bid, t0).unwrap();
+        assert_eq!(order_book.order_map.get(&2).unwrap().price, 20);
+
+        let plain_bid = _create_test_order_with_quantity(3, 18, 5, t2, OrderSide::Bid);
+        order_book.add_order(plain_bid, t2).unwrap();
+
+        // Best ask improves to 20, so the pegged bid re-derives to 18 and
+        // now shares a price level with the plain bid that has rested
+        // there since t2.
+        let better_ask = _create_test_order_with_quantity(4, 20, 5, t3, OrderSide::Ask);
+        order_book.add_order(better_ask, t3).unwrap();
+        assert_eq!(order_book.order_map.get(&2).unwrap().price, 18);
+
+        let first = order_book.next_bid_order(t3).expect("plain bid should pop first");
+        assert_eq!(first.id, 3);
+        let second = order_book.next_bid_order(t3).expect("pegged bid should pop second");
+        assert_eq!(second.id, 2);
+    }
 }